@@ -18,24 +18,30 @@ use std::path::PathBuf;
 use std::sync::Arc;
 
 use dir::default_data_path;
-use ethcore::client::{Client, BlockChainClient, BlockId};
-use ethcore::transaction::{Transaction, Action};
-use ethsync::LightSync;
-use futures::{future, IntoFuture, Future, BoxFuture};
 use hash_fetch::fetch::Client as FetchClient;
 use hash_fetch::urlhint::ContractClient;
 use helpers::replace_home;
-use light::client::Client as LightClient;
-use light::on_demand::{self, OnDemand};
 use rpc_apis::SignerService;
 use parity_reactor;
-use util::{Bytes, Address};
+use util::U256;
+
+mod health;
+
+pub use self::health::{NodeHealth, HealthStatus, HealthState, Health, PeerThresholds, TimeChecker};
+// `FullRegistrar`/`LightRegistrar`/`RegistryClient`/`UrlHintResolver` now live
+// in the standalone `registry` module so they compile and can be wired into
+// `Dependencies` whether or not the `dapps` feature is enabled.
+pub use registry::{FullRegistrar, LightRegistrar, RegistryClient, UrlHintResolver, Content};
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Configuration {
 	pub enabled: bool,
 	pub dapps_path: PathBuf,
 	pub extra_dapps: Vec<PathBuf>,
+	/// Gas cap for light-client registry probe calls. `None` defaults to a
+	/// fraction of the chain's current block gas limit rather than a fixed
+	/// constant, so lookups don't over-allocate on non-mainnet specs.
+	pub registry_call_gas_cap: Option<U256>,
 }
 
 impl Default for Configuration {
@@ -45,79 +51,7 @@ impl Default for Configuration {
 			enabled: true,
 			dapps_path: replace_home(&data_dir, "$BASE/dapps").into(),
 			extra_dapps: vec![],
-		}
-	}
-}
-
-/// Registrar implementation of the full client.
-pub struct FullRegistrar {
-	/// Handle to the full client.
-	pub client: Arc<Client>,
-}
-
-impl ContractClient for FullRegistrar {
-	fn registrar(&self) -> Result<Address, String> {
-		self.client.additional_params().get("registrar")
-			 .ok_or_else(|| "Registrar not defined.".into())
-			 .and_then(|registrar| {
-				 registrar.parse().map_err(|e| format!("Invalid registrar address: {:?}", e))
-			 })
-	}
-
-	fn call(&self, address: Address, data: Bytes) -> BoxFuture<Bytes, String> {
-		self.client.call_contract(BlockId::Latest, address, data)
-			.into_future()
-			.boxed()
-	}
-}
-
-/// Registrar implementation for the light client.
-pub struct LightRegistrar {
-	/// The light client.
-	pub client: Arc<LightClient>,
-	/// Handle to the on-demand service.
-	pub on_demand: Arc<OnDemand>,
-	/// Handle to the light network service.
-	pub sync: Arc<LightSync>,
-}
-
-impl ContractClient for LightRegistrar {
-	fn registrar(&self) -> Result<Address, String> {
-		self.client.engine().additional_params().get("registrar")
-			 .ok_or_else(|| "Registrar not defined.".into())
-			 .and_then(|registrar| {
-				 registrar.parse().map_err(|e| format!("Invalid registrar address: {:?}", e))
-			 })
-	}
-
-	fn call(&self, address: Address, data: Bytes) -> BoxFuture<Bytes, String> {
-		let (header, env_info) = (self.client.best_block_header(), self.client.latest_env_info());
-
-		let maybe_future = self.sync.with_context(move |ctx| {
-			self.on_demand
-				.transaction_proof(ctx, on_demand::request::TransactionProof {
-					tx: Transaction {
-						nonce: self.client.engine().account_start_nonce(),
-						action: Action::Call(address),
-						gas: 50_000_000.into(),
-						gas_price: 0.into(),
-						value: 0.into(),
-						data: data,
-					}.fake_sign(Address::default()),
-					header: header,
-					env_info: env_info,
-					engine: self.client.engine().clone(),
-				})
-				.then(|res| match res {
-					Ok(Ok(executed)) => Ok(executed.output),
-					Ok(Err(e)) => Err(format!("Failed to execute transaction: {}", e)),
-					Err(_) => Err(format!("On-demand service dropped request unexpectedly.")),
-				})
-		});
-
-		match maybe_future {
-			Some(fut) => fut.boxed(),
-			None => future::err("cannot query registry: network disabled".into()).boxed(),
+			registry_call_gas_cap: None,
 		}
 	}
 }
@@ -125,8 +59,14 @@ impl ContractClient for LightRegistrar {
 // TODO: light client implementation forwarding to OnDemand and waiting for future
 // to resolve.
 pub struct Dependencies {
-	pub sync_status: Arc<SyncStatus>,
+	pub health: Arc<NodeHealth>,
 	pub contract_client: Arc<ContractClient>,
+	/// Headless registry resolution/reverse-resolution, usable over RPC
+	/// whether or not WebApps support was compiled in. Built by the caller
+	/// from `Configuration::registry_call_gas_cap` via
+	/// `LightRegistrar::with_gas_cap` on light clients, so the cap takes
+	/// effect before `Dependencies` is assembled.
+	pub registry: Arc<RegistryClient>,
 	pub remote: parity_reactor::TokioRemote,
 	pub fetch: FetchClient,
 	pub signer: Arc<SignerService>,
@@ -194,6 +134,10 @@ mod server {
 		let signer = deps.signer.clone();
 		let parity_remote = parity_reactor::Remote::new(deps.remote.clone());
 		let web_proxy_tokens = Arc::new(move |token| signer.is_valid_web_proxy_access_token(&token));
+		let health = deps.health.clone();
+		// `SyncStatus` historically reports `true` while the node *is*
+		// syncing, so the middleware can answer 412 until it's healthy.
+		let sync_status: Arc<SyncStatus> = Arc::new(move || !health.is_healthy());
 
 		Ok(parity_dapps::Middleware::new(
 			parity_remote,
@@ -201,7 +145,7 @@ mod server {
 			dapps_path,
 			extra_dapps,
 			deps.contract_client,
-			deps.sync_status,
+			sync_status,
 			web_proxy_tokens,
 			deps.fetch.clone(),
 		))
@@ -0,0 +1,350 @@
+// Copyright 2015-2017 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Node health: sync, peer-count and clock-drift reporting shared by the
+//! dapps middleware and RPC.
+
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use util::Mutex;
+
+/// How healthy a particular aspect of the node is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HealthState {
+	/// Everything is fine.
+	Ok,
+	/// Not ideal, but not a reason to distrust the node yet.
+	NeedsAttention,
+	/// The node should not be trusted right now.
+	Bad,
+}
+
+/// A single health check result, with a human-readable explanation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Health {
+	/// Overall state of this check.
+	pub state: HealthState,
+	/// Short, human-readable details (e.g. peer count, clock offset).
+	pub details: String,
+}
+
+/// Aggregate health of the node, as reported to dapps and RPC consumers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthStatus {
+	/// Whether the client believes it is in sync with the network.
+	pub sync: Health,
+	/// Whether the client has a healthy number of peers.
+	pub peers: Health,
+	/// Whether the client's clock is in sync with the network.
+	pub time: Health,
+}
+
+/// Peer-count thresholds supplied by the network layer.
+#[derive(Debug, Clone, Copy)]
+pub struct PeerThresholds {
+	/// Below this many peers we report `Bad`.
+	pub bad: usize,
+	/// Below this many peers (but at or above `bad`) we report `NeedsAttention`.
+	pub needs_attention: usize,
+}
+
+impl Default for PeerThresholds {
+	fn default() -> Self {
+		PeerThresholds {
+			bad: 1,
+			needs_attention: 3,
+		}
+	}
+}
+
+fn peer_health(peers: usize, thresholds: PeerThresholds) -> Health {
+	let state = if peers < thresholds.bad {
+		HealthState::Bad
+	} else if peers < thresholds.needs_attention {
+		HealthState::NeedsAttention
+	} else {
+		HealthState::Ok
+	};
+
+	Health {
+		state: state,
+		details: format!("{} peer(s)", peers),
+	}
+}
+
+/// Offset from the NTP epoch (1900-01-01) to the Unix epoch (1970-01-01), in seconds.
+const NTP_EPOCH_OFFSET: u64 = 2_208_988_800;
+
+/// A 32.32 fixed-point NTP timestamp, converted to a `Duration` since the Unix epoch.
+fn ntp_timestamp_to_duration(seconds: u32, fraction: u32) -> Duration {
+	let secs = (seconds as u64).saturating_sub(NTP_EPOCH_OFFSET);
+	let nanos = ((fraction as u64) * 1_000_000_000) >> 32;
+	Duration::new(secs, nanos as u32)
+}
+
+fn system_time_to_ntp(time: SystemTime) -> (u32, u32) {
+	let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or(Duration::new(0, 0));
+	let seconds = since_epoch.as_secs() + NTP_EPOCH_OFFSET;
+	let fraction = (((since_epoch.subsec_nanos() as u64) << 32) / 1_000_000_000) as u32;
+	(seconds as u32, fraction as u32)
+}
+
+/// Packs a `u32` into 4 big-endian bytes by hand, since `u32::to_be_bytes`
+/// requires a newer `rustc` than this crate otherwise depends on.
+fn u32_to_be(value: u32) -> [u8; 4] {
+	[
+		(value >> 24) as u8,
+		(value >> 16) as u8,
+		(value >> 8) as u8,
+		value as u8,
+	]
+}
+
+/// Unpacks 4 big-endian bytes into a `u32` by hand; see `u32_to_be`.
+fn u32_from_be(bytes: &[u8]) -> u32 {
+	((bytes[0] as u32) << 24) | ((bytes[1] as u32) << 16) | ((bytes[2] as u32) << 8) | (bytes[3] as u32)
+}
+
+fn signed_duration(a: Duration, b: Duration) -> (Duration, bool) {
+	if a >= b {
+		(a - b, false)
+	} else {
+		(b - a, true)
+	}
+}
+
+/// Result of a single SNTP round-trip: clock offset and round-trip delay.
+#[derive(Debug, Clone, Copy)]
+pub struct TimeOffset {
+	/// Magnitude of the offset `((T2-T1)+(T3-T4))/2` between the server's
+	/// clock and the local clock; `offset_negative` is set when the local
+	/// clock is ahead of the server's.
+	pub offset: Duration,
+	pub offset_negative: bool,
+	/// Total round-trip delay of the SNTP exchange.
+	pub round_trip_delay: Duration,
+}
+
+/// Performs a single SNTP query against `server` (e.g. `"pool.ntp.org:123"`).
+pub fn query_sntp(server: &str) -> Result<TimeOffset, String> {
+	let addr = server.to_socket_addrs()
+		.map_err(|e| format!("invalid NTP server address: {}", e))?
+		.next()
+		.ok_or_else(|| "could not resolve NTP server address".to_owned())?;
+
+	let socket = UdpSocket::bind("0.0.0.0:0")
+		.map_err(|e| format!("could not open UDP socket: {}", e))?;
+	socket.set_read_timeout(Some(Duration::from_secs(5)))
+		.map_err(|e| format!("could not set socket timeout: {}", e))?;
+
+	let mut packet = [0u8; 48];
+	// LI = 0 (no warning), VN = 3, Mode = 3 (client)
+	packet[0] = 0b00_011_011;
+
+	let t1 = SystemTime::now();
+	let (t1_secs, t1_frac) = system_time_to_ntp(t1);
+	packet[40..44].copy_from_slice(&u32_to_be(t1_secs));
+	packet[44..48].copy_from_slice(&u32_to_be(t1_frac));
+
+	socket.send_to(&packet, addr).map_err(|e| format!("could not send NTP request: {}", e))?;
+
+	let mut response = [0u8; 48];
+	let (read, _) = socket.recv_from(&mut response)
+		.map_err(|e| format!("could not read NTP response: {}", e))?;
+	let t4 = SystemTime::now();
+
+	if read < 48 {
+		return Err("truncated NTP response".into());
+	}
+
+	let t2 = ntp_timestamp_to_duration(
+		u32_from_be(&response[32..36]),
+		u32_from_be(&response[36..40]),
+	);
+	let t3 = ntp_timestamp_to_duration(
+		u32_from_be(&response[40..44]),
+		u32_from_be(&response[44..48]),
+	);
+
+	let t1 = t1.duration_since(UNIX_EPOCH).map_err(|e| format!("system clock error: {}", e))?;
+	let t4 = t4.duration_since(UNIX_EPOCH).map_err(|e| format!("system clock error: {}", e))?;
+
+	// offset = ((T2-T1) + (T3-T4)) / 2
+	let (d1, d1_neg) = signed_duration(t2, t1);
+	let (d2, d2_neg) = signed_duration(t3, t4);
+	let (offset, offset_negative) = if d1_neg == d2_neg {
+		((d1 + d2) / 2, d1_neg)
+	} else if d1 >= d2 {
+		((d1 - d2) / 2, d1_neg)
+	} else {
+		((d2 - d1) / 2, d2_neg)
+	};
+
+	// round-trip delay = (T4-T1) - (T3-T2)
+	let (total, _) = signed_duration(t4, t1);
+	let (server_side, _) = signed_duration(t3, t2);
+	let round_trip_delay = if total >= server_side { total - server_side } else { Duration::new(0, 0) };
+
+	Ok(TimeOffset {
+		offset: offset,
+		offset_negative: offset_negative,
+		round_trip_delay: round_trip_delay,
+	})
+}
+
+fn time_health(offset: &Result<TimeOffset, String>) -> Health {
+	match *offset {
+		Ok(ref offset) => {
+			let millis = offset.offset.as_secs() * 1000 + (offset.offset.subsec_nanos() / 1_000_000) as u64;
+			let sign = if offset.offset_negative { "-" } else { "" };
+			let details = format!("clock offset {}{}ms", sign, millis);
+
+			let state = if offset.offset < Duration::from_millis(500) {
+				HealthState::Ok
+			} else if offset.offset < Duration::from_secs(4) {
+				HealthState::NeedsAttention
+			} else {
+				HealthState::Bad
+			};
+
+			Health { state: state, details: details }
+		}
+		Err(ref e) => Health {
+			state: HealthState::NeedsAttention,
+			details: format!("NTP check failed: {}", e),
+		},
+	}
+}
+
+struct CachedTime {
+	checked_at: Instant,
+	result: Result<TimeOffset, String>,
+}
+
+/// Caches the result of an SNTP poll for `cache_for`, so we don't hammer the
+/// configured NTP pool server on every health check.
+pub struct TimeChecker {
+	ntp_server: String,
+	cache_for: Duration,
+	cached: Mutex<Option<CachedTime>>,
+}
+
+impl TimeChecker {
+	/// Creates a new checker polling `ntp_server` (e.g. `"pool.ntp.org:123"`),
+	/// caching results for `cache_for`.
+	pub fn new(ntp_server: String, cache_for: Duration) -> Self {
+		TimeChecker {
+			ntp_server: ntp_server,
+			cache_for: cache_for,
+			cached: Mutex::new(None),
+		}
+	}
+
+	/// Returns the current clock-drift health, polling NTP only if the
+	/// cached result has expired.
+	pub fn health(&self) -> Health {
+		let mut cached = self.cached.lock();
+		let needs_refresh = match *cached {
+			Some(ref c) => c.checked_at.elapsed() >= self.cache_for,
+			None => true,
+		};
+
+		if needs_refresh {
+			let result = query_sntp(&self.ntp_server);
+			*cached = Some(CachedTime { checked_at: Instant::now(), result: result });
+		}
+
+		time_health(&cached.as_ref().expect("just set above").result)
+	}
+}
+
+/// A widened replacement for the old bare `Fn() -> bool` sync status closure.
+///
+/// Reports not just whether the client is syncing, but whether it has enough
+/// peers and a trustworthy clock, so dapps and RPC can explain *why* a node
+/// is considered out of consensus.
+pub struct NodeHealth {
+	is_major_syncing: Arc<Fn() -> bool + Send + Sync>,
+	peer_count: Arc<Fn() -> usize + Send + Sync>,
+	peer_thresholds: PeerThresholds,
+	time: TimeChecker,
+}
+
+impl NodeHealth {
+	/// Creates a new `NodeHealth`.
+	pub fn new(
+		is_major_syncing: Arc<Fn() -> bool + Send + Sync>,
+		peer_count: Arc<Fn() -> usize + Send + Sync>,
+		peer_thresholds: PeerThresholds,
+		time: TimeChecker,
+	) -> Self {
+		NodeHealth {
+			is_major_syncing: is_major_syncing,
+			peer_count: peer_count,
+			peer_thresholds: peer_thresholds,
+			time: time,
+		}
+	}
+
+	/// Returns whether the client currently believes itself healthy enough
+	/// to serve dapps (kept for call-sites that only need a bool, e.g. the
+	/// dapps middleware's 412 check).
+	pub fn is_healthy(&self) -> bool {
+		!(self.is_major_syncing)()
+	}
+
+	/// Returns the full health breakdown.
+	pub fn status(&self) -> HealthStatus {
+		let syncing = (self.is_major_syncing)();
+		let peers = (self.peer_count)();
+
+		HealthStatus {
+			sync: Health {
+				state: if syncing { HealthState::NeedsAttention } else { HealthState::Ok },
+				details: if syncing { "still syncing".into() } else { "in sync".into() },
+			},
+			peers: peer_health(peers, self.peer_thresholds),
+			time: self.time.health(),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::time::Duration;
+
+	#[test]
+	fn classifies_peer_counts() {
+		let thresholds = PeerThresholds::default();
+		assert_eq!(peer_health(0, thresholds).state, HealthState::Bad);
+		assert_eq!(peer_health(1, thresholds).state, HealthState::NeedsAttention);
+		assert_eq!(peer_health(3, thresholds).state, HealthState::Ok);
+	}
+
+	#[test]
+	fn classifies_time_offsets() {
+		let small = Ok(TimeOffset { offset: Duration::from_millis(100), offset_negative: false, round_trip_delay: Duration::from_millis(10) });
+		let medium = Ok(TimeOffset { offset: Duration::from_secs(2), offset_negative: true, round_trip_delay: Duration::from_millis(10) });
+		let large = Ok(TimeOffset { offset: Duration::from_secs(10), offset_negative: false, round_trip_delay: Duration::from_millis(10) });
+
+		assert_eq!(time_health(&small).state, HealthState::Ok);
+		assert_eq!(time_health(&medium).state, HealthState::NeedsAttention);
+		assert_eq!(time_health(&large).state, HealthState::Bad);
+	}
+}
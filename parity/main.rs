@@ -0,0 +1,57 @@
+// Copyright 2015-2017 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Crate root wiring for the subset of subsystems touched by this series:
+//! node health, on-chain registry resolution and the (optional) dapps
+//! middleware. Process bootstrap proper — configuration parsing, client and
+//! sync construction, RPC server startup — lives elsewhere and is out of
+//! scope here.
+
+mod dapps;
+mod registry;
+
+use std::sync::Arc;
+
+use hash_fetch::fetch::Client as FetchClient;
+use hash_fetch::urlhint::ContractClient;
+use parity_reactor;
+use rpc_apis::SignerService;
+
+use dapps::{Dependencies, NodeHealth};
+use registry::RegistryClient;
+
+/// Assembles the `Dependencies` shared by the RPC and the dapps middleware.
+///
+/// Neither `registry` nor `dapps` carries a `#[cfg(feature = "dapps")]` of
+/// its own — only `dapps::server`'s `dapps_middleware` does — so this
+/// builds the same `RegistryClient` whether or not WebApps support was
+/// compiled in.
+pub fn build_dependencies(
+	contract_client: Arc<ContractClient>,
+	health: Arc<NodeHealth>,
+	remote: parity_reactor::TokioRemote,
+	fetch: FetchClient,
+	signer: Arc<SignerService>,
+) -> Dependencies {
+	Dependencies {
+		health: health,
+		registry: Arc::new(RegistryClient::new(contract_client.clone())),
+		contract_client: contract_client,
+		remote: remote,
+		fetch: fetch,
+		signer: signer,
+	}
+}
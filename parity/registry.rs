@@ -0,0 +1,347 @@
+// Copyright 2015-2017 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! On-chain registry resolution: `ContractClient` implementations for the
+//! full and light clients, and the higher-level `RegistryClient`/
+//! `UrlHintResolver` built on top of them.
+//!
+//! This module is independent of the `dapps` cargo feature — name/registry
+//! resolution is useful headless, so it compiles and is wired into
+//! `Dependencies` whether or not WebApps support is built in. It defines no
+//! RPC methods of its own.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use ethcore::client::{Client, BlockChainClient, BlockId};
+use ethcore::executed::Executed;
+use ethcore::transaction::{Transaction, Action};
+use ethsync::LightSync;
+use futures::{future, IntoFuture, Future, BoxFuture};
+use hash_fetch::urlhint::ContractClient;
+use light::client::Client as LightClient;
+use light::on_demand::{self, OnDemand};
+use util::{Bytes, Address, H256, U256, Mutex};
+use vm::Error as VmError;
+
+mod abi;
+mod urlhint_resolver;
+
+use self::abi::{selector, encode_call_word_and_bytes, decode_address, decode_dynamic_string, name_hash};
+pub use self::urlhint_resolver::{UrlHintResolver, Content};
+
+/// Fraction of the chain's current block gas limit used as the default gas
+/// cap for a light-client registry probe, when no explicit cap is
+/// configured. Mainnet's `50_000_000` constant over-allocates badly on
+/// chains with a much lower block gas limit, so we scale with the spec
+/// instead of hardcoding it.
+const DEFAULT_GAS_CAP_DIVISOR: u64 = 2;
+
+/// Distinguishes why a light-client registry probe failed, so callers can
+/// tell "the gas cap was too small" apart from "the contract reverted" and
+/// degrade gracefully (e.g. by retrying with a higher cap) rather than
+/// treating every failure the same.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RegistryCallError {
+	/// The allotted gas was exhausted before the call completed.
+	OutOfGas,
+	/// The call ran to completion but the contract reverted.
+	Reverted(String),
+	/// Any other execution or transport failure.
+	Other(String),
+}
+
+impl RegistryCallError {
+	/// Classifies the outcome of a completed `transaction_proof`: a call that
+	/// ran out of gas or reverted still comes back as `Ok(Executed)` with the
+	/// failure recorded in `exception`, rather than as an `Err`, so this
+	/// inspects the VM error variant directly instead of pattern-matching on
+	/// a formatted error string.
+	fn from_executed(executed: Executed) -> Result<Bytes, Self> {
+		match executed.exception {
+			Some(VmError::OutOfGas) => Err(RegistryCallError::OutOfGas),
+			Some(VmError::Reverted) => Err(RegistryCallError::Reverted("execution reverted".into())),
+			Some(ref other) => Err(RegistryCallError::Other(format!("{}", other))),
+			None => Ok(executed.output),
+		}
+	}
+}
+
+impl ::std::fmt::Display for RegistryCallError {
+	fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+		match *self {
+			RegistryCallError::OutOfGas => write!(f, "registry call ran out of gas"),
+			RegistryCallError::Reverted(ref e) => write!(f, "registry call reverted: {}", e),
+			RegistryCallError::Other(ref e) => write!(f, "registry call failed: {}", e),
+		}
+	}
+}
+
+/// Registrar implementation of the full client.
+pub struct FullRegistrar {
+	/// Handle to the full client.
+	pub client: Arc<Client>,
+}
+
+impl ContractClient for FullRegistrar {
+	fn registrar(&self) -> Result<Address, String> {
+		self.client.additional_params().get("registrar")
+			 .ok_or_else(|| "Registrar not defined.".into())
+			 .and_then(|registrar| {
+				 registrar.parse().map_err(|e| format!("Invalid registrar address: {:?}", e))
+			 })
+	}
+
+	fn call(&self, address: Address, data: Bytes) -> BoxFuture<Bytes, String> {
+		self.client.call_contract(BlockId::Latest, address, data)
+			.into_future()
+			.boxed()
+	}
+}
+
+/// Cache of executed `(best_block_hash, address, data)` -> output, valid
+/// only for as long as `best_block` hasn't moved on.
+struct RegistryCache {
+	best_block: H256,
+	entries: HashMap<(Address, Bytes), Bytes>,
+}
+
+impl RegistryCache {
+	fn new(best_block: H256) -> Self {
+		RegistryCache { best_block: best_block, entries: HashMap::new() }
+	}
+}
+
+/// Registrar implementation for the light client.
+///
+/// Memoizes executed calls until the best block changes and batches several
+/// lookups into a single `with_context` scope, since each call otherwise
+/// requires a full `transaction_proof` round-trip through `OnDemand`.
+pub struct LightRegistrar {
+	/// The light client.
+	pub client: Arc<LightClient>,
+	/// Handle to the on-demand service.
+	pub on_demand: Arc<OnDemand>,
+	/// Handle to the light network service.
+	pub sync: Arc<LightSync>,
+	/// Explicit gas cap for registry probes, overriding the spec-driven
+	/// default of a fraction of the current block gas limit.
+	pub gas_cap: Option<U256>,
+	cache: Arc<Mutex<RegistryCache>>,
+}
+
+impl LightRegistrar {
+	/// Creates a new `LightRegistrar` with an empty cache, using the
+	/// spec-driven default gas cap.
+	pub fn new(client: Arc<LightClient>, on_demand: Arc<OnDemand>, sync: Arc<LightSync>) -> Self {
+		Self::with_gas_cap(client, on_demand, sync, None)
+	}
+
+	/// Creates a new `LightRegistrar`, capping probe transactions at
+	/// `gas_cap` instead of the spec-driven default when given.
+	pub fn with_gas_cap(client: Arc<LightClient>, on_demand: Arc<OnDemand>, sync: Arc<LightSync>, gas_cap: Option<U256>) -> Self {
+		let best_block = client.best_block_header().hash();
+		LightRegistrar {
+			client: client,
+			on_demand: on_demand,
+			sync: sync,
+			gas_cap: gas_cap,
+			cache: Arc::new(Mutex::new(RegistryCache::new(best_block))),
+		}
+	}
+
+
+	/// Clears the cache if the best block has moved on since it was
+	/// populated, keeping it scoped to a single block.
+	fn refresh_cache(&self) {
+		let best_block = self.client.best_block_header().hash();
+		let mut cache = self.cache.lock();
+		if cache.best_block != best_block {
+			*cache = RegistryCache::new(best_block);
+		}
+	}
+
+	/// Batches several registry calls into a single `with_context` scope,
+	/// issuing the on-demand proofs concurrently and serving any entries
+	/// already cached for the current best block straight from memory.
+	///
+	/// Per-query failures (e.g. running out of the configured gas cap) are
+	/// carried as `RegistryCallError` in the per-item `Result` rather than
+	/// failing the whole batch, so a caller can e.g. retry just the failed
+	/// entries with a higher cap.
+	pub fn call_many(&self, queries: Vec<(Address, Bytes)>) -> BoxFuture<Vec<Result<Bytes, RegistryCallError>>, String> {
+		self.refresh_cache();
+
+		let mut results: Vec<Option<Result<Bytes, RegistryCallError>>> = Vec::with_capacity(queries.len());
+		let mut pending = Vec::new();
+		{
+			let cache = self.cache.lock();
+			for (address, data) in queries {
+				match cache.entries.get(&(address, data.clone())) {
+					Some(cached) => results.push(Some(Ok(cached.clone()))),
+					None => {
+						pending.push((results.len(), address, data));
+						results.push(None);
+					}
+				}
+			}
+		}
+
+		if pending.is_empty() {
+			return future::ok(results.into_iter().map(|r| r.expect("filled above")).collect()).boxed();
+		}
+
+		let (header, env_info) = (self.client.best_block_header(), self.client.latest_env_info());
+		let block_hash = header.hash();
+		let engine = self.client.engine().clone();
+		let account_start_nonce = self.client.engine().account_start_nonce();
+		let on_demand = self.on_demand.clone();
+		let cache = self.cache.clone();
+
+		// Default to a fraction of the current block's gas limit rather than
+		// a constant, so probes don't over-allocate (and thus fail) on
+		// chains with a much lower gas limit than mainnet.
+		let gas = self.gas_cap.unwrap_or_else(|| header.gas_limit() / DEFAULT_GAS_CAP_DIVISOR.into());
+
+		let requests: Vec<_> = pending.iter().map(|&(_, address, ref data)| {
+			on_demand::request::TransactionProof {
+				tx: Transaction {
+					nonce: account_start_nonce,
+					action: Action::Call(address),
+					gas: gas,
+					gas_price: 0.into(),
+					value: 0.into(),
+					data: data.clone(),
+				}.fake_sign(Address::default()),
+				header: header.clone(),
+				env_info: env_info.clone(),
+				engine: engine.clone(),
+			}
+		}).collect();
+
+		let maybe_future = self.sync.with_context(move |ctx| {
+			// Each request resolves to `Ok(Result<Bytes, RegistryCallError>)`
+			// so a per-query execution failure (e.g. out of gas) doesn't
+			// short-circuit `join_all` and fail the whole batch; only a
+			// dropped on-demand request does that.
+			future::join_all(requests.into_iter().map(move |req| {
+				on_demand.transaction_proof(ctx, req).then(|res| match res {
+					Ok(Ok(executed)) => Ok(RegistryCallError::from_executed(executed)),
+					Ok(Err(e)) => Ok(Err(RegistryCallError::Other(format!("{}", e)))),
+					Err(_) => Err(format!("On-demand service dropped request unexpectedly.")),
+				})
+			}).collect::<Vec<_>>())
+		});
+
+		match maybe_future {
+			Some(fut) => fut
+				.map(move |outputs| {
+					let mut cache = cache.lock();
+					// Only memoize outputs if the cache still belongs to the
+					// block they were executed against.
+					let cache_current = cache.best_block == block_hash;
+					for (&(index, address, ref data), output) in pending.iter().zip(outputs.into_iter()) {
+						if let Ok(ref bytes) = output {
+							if cache_current {
+								cache.entries.insert((address, data.clone()), bytes.clone());
+							}
+						}
+						results[index] = Some(output);
+					}
+					results.into_iter().map(|r| r.expect("filled above")).collect()
+				})
+				.boxed(),
+			None => future::err("cannot query registry: network disabled".into()).boxed(),
+		}
+	}
+}
+
+impl ContractClient for LightRegistrar {
+	fn registrar(&self) -> Result<Address, String> {
+		self.client.engine().additional_params().get("registrar")
+			 .ok_or_else(|| "Registrar not defined.".into())
+			 .and_then(|registrar| {
+				 registrar.parse().map_err(|e| format!("Invalid registrar address: {:?}", e))
+			 })
+	}
+
+	fn call(&self, address: Address, data: Bytes) -> BoxFuture<Bytes, String> {
+		self.call_many(vec![(address, data)])
+			.and_then(|mut results| {
+				results.pop().expect("single query produces a single result").map_err(|e| e.to_string())
+			})
+			.boxed()
+	}
+}
+
+/// Headless service for resolving and reversing registry entries: registrar
+/// address lookup, forward `get`, and reverse-name resolution via the
+/// registry's `reverse(address)` call. Available regardless of whether
+/// WebApps support was compiled in; this module registers no RPC methods
+/// itself.
+pub struct RegistryClient {
+	contract_client: Arc<ContractClient>,
+}
+
+impl RegistryClient {
+	/// Creates a new `RegistryClient` on top of either `FullRegistrar` or
+	/// `LightRegistrar`.
+	pub fn new(contract_client: Arc<ContractClient>) -> Self {
+		RegistryClient { contract_client: contract_client }
+	}
+
+	/// Looks up the address of the registrar contract itself.
+	pub fn registrar_address(&self) -> Result<Address, String> {
+		self.contract_client.registrar()
+	}
+
+	/// Forward-resolves `name` (e.g. `"eth"`) to the address it is
+	/// registered to, via the registrar's `get(bytes32 name, string key)`.
+	/// `key` is the dynamic `string` "A" convention used for address
+	/// entries, so it has to be ABI-encoded as an offset plus a
+	/// length-prefixed, padded byte string rather than a bare word.
+	pub fn resolve(&self, name: &str) -> BoxFuture<Address, String> {
+		let registrar = match self.contract_client.registrar() {
+			Ok(address) => address,
+			Err(e) => return future::err(e).boxed(),
+		};
+
+		let data = encode_call_word_and_bytes("get(bytes32,string)", &name_hash(name), b"A");
+
+		self.contract_client.call(registrar, data)
+			.and_then(|result| decode_address(&result, 0).ok_or_else(|| "registry returned malformed address".to_owned()))
+			.boxed()
+	}
+
+	/// Reverse-resolves `address` back to the name that was registered
+	/// against it, via the registry's `reverse(address)` ABI.
+	pub fn reverse(&self, address: Address) -> BoxFuture<String, String> {
+		let registrar = match self.contract_client.registrar() {
+			Ok(address) => address,
+			Err(e) => return future::err(e).boxed(),
+		};
+
+		let mut data = selector("reverse(address)");
+		data.extend_from_slice(&self::abi::encode_word(&address.0));
+
+		self.contract_client.call(registrar, data)
+			.and_then(|result| {
+				decode_dynamic_string(&result, 0)
+					.ok_or_else(|| "registry returned malformed reverse entry".to_owned())
+			})
+			.boxed()
+	}
+}
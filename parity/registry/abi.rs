@@ -0,0 +1,122 @@
+// Copyright 2015-2017 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Minimal ABI encode/decode helpers shared by `RegistryClient` and
+//! `UrlHintResolver` — just enough for the handful of registry contract
+//! calls this module makes, not a general-purpose ABI codec.
+
+use util::{Bytes, Address, Hashable};
+
+/// Packs a `u32` into 4 big-endian bytes by hand, since `u32::to_be_bytes`
+/// requires a newer `rustc` than this crate otherwise depends on.
+fn u32_to_be(value: u32) -> [u8; 4] {
+	[
+		(value >> 24) as u8,
+		(value >> 16) as u8,
+		(value >> 8) as u8,
+		value as u8,
+	]
+}
+
+/// First 4 bytes of `keccak256(signature)`, e.g. `"get(bytes32,string)"`.
+pub fn selector(signature: &str) -> Bytes {
+	signature.sha3()[0..4].to_vec()
+}
+
+/// Right-aligns `data` into a single 32-byte ABI word.
+pub fn encode_word(data: &[u8]) -> Bytes {
+	let mut word = vec![0u8; 32];
+	let len = data.len().min(32);
+	word[32 - len..].copy_from_slice(&data[data.len() - len..]);
+	word
+}
+
+/// Encodes `len` as a right-aligned ABI word (an offset or length field).
+fn encode_length(len: usize) -> Bytes {
+	encode_word(&u32_to_be(len as u32))
+}
+
+/// Encodes a call taking a single static `bytes32` word, e.g.
+/// `entries(bytes32)`.
+pub fn encode_call_word(signature: &str, word: &[u8]) -> Bytes {
+	let mut data = selector(signature);
+	data.extend_from_slice(&encode_word(word));
+	data
+}
+
+/// Encodes a call taking one static `bytes32` word followed by one dynamic
+/// `string`/`bytes` argument, e.g. `get(bytes32 name, string key)`.
+///
+/// `string`/`bytes` are dynamic ABI types: the head only carries an offset
+/// to where the length-prefixed, right-padded bytes live in the tail.
+pub fn encode_call_word_and_bytes(signature: &str, word: &[u8], dynamic: &[u8]) -> Bytes {
+	let mut data = selector(signature);
+	data.extend_from_slice(&encode_word(word));
+	// Two head words (the bytes32 and this offset) precede the tail.
+	data.extend_from_slice(&encode_length(2 * 32));
+	data.extend_from_slice(&encode_length(dynamic.len()));
+	data.extend_from_slice(dynamic);
+	while data.len() % 32 != 0 {
+		data.push(0);
+	}
+	data
+}
+
+/// Decodes the `address` stored in the given head word.
+pub fn decode_address(data: &[u8], word: usize) -> Option<Address> {
+	let start = word * 32 + 12;
+	data.get(start..start + 20).map(Address::from_slice)
+}
+
+/// Decodes the `uint8` stored in the given head word.
+pub fn decode_uint8(data: &[u8], word: usize) -> Option<u8> {
+	data.get(word * 32 + 31).cloned()
+}
+
+/// Decodes the `bytes20` stored in the given head word.
+pub fn decode_bytes20(data: &[u8], word: usize) -> Option<[u8; 20]> {
+	let start = word * 32;
+	let slice = data.get(start..start + 20)?;
+	let mut out = [0u8; 20];
+	out.copy_from_slice(slice);
+	Some(out)
+}
+
+fn decode_length_at(data: &[u8], at: usize) -> Option<usize> {
+	data.get(at..at + 32).map(|word| word[28..32].iter().fold(0usize, |acc, b| acc << 8 | *b as usize))
+}
+
+/// Decodes a dynamic `string`/`bytes` argument/return value whose offset is
+/// stored in the given head word.
+pub fn decode_dynamic_bytes(data: &[u8], offset_word: usize) -> Option<Vec<u8>> {
+	let offset = decode_length_at(data, offset_word * 32)?;
+	let len = decode_length_at(data, offset)?;
+	data.get(offset + 32..offset + 32 + len).map(|b| b.to_vec())
+}
+
+/// Decodes a dynamic `string` argument/return value whose offset is stored
+/// in the given head word.
+pub fn decode_dynamic_string(data: &[u8], offset_word: usize) -> Option<String> {
+	decode_dynamic_bytes(data, offset_word).and_then(|bytes| String::from_utf8(bytes).ok())
+}
+
+/// `keccak256` of `name`, used as the registry key for name lookups.
+pub fn name_hash(name: &str) -> [u8; 32] {
+	let hash = name.sha3();
+	let mut out = [0u8; 32];
+	out.copy_from_slice(&hash);
+	out
+}
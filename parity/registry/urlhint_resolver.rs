@@ -0,0 +1,232 @@
+// Copyright 2015-2017 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Resolves content hashes against the on-chain URLHint registry into
+//! installable dapp content, validating the fetched bytes against the
+//! hash the caller asked for.
+
+use std::sync::Arc;
+
+use futures::{BoxFuture, Future};
+use hash_fetch::fetch::Client as FetchClient;
+use hash_fetch::urlhint::ContractClient;
+use util::{Bytes, Address, H256, Hashable};
+
+use super::abi::{encode_call_word, encode_call_word_and_bytes, decode_uint8, decode_address, decode_bytes20, decode_dynamic_string, name_hash};
+
+/// A resolved, installable piece of dapp content.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Content {
+	/// URL the content was (or should be) fetched from.
+	pub url: String,
+	/// MIME type to serve the content as.
+	pub mime: String,
+	/// Account that registered this content hash.
+	pub owner: Address,
+}
+
+/// Entry kind as stored in the URLHint registry.
+#[derive(Debug, Clone, PartialEq)]
+enum Entry {
+	/// A plain URL entry.
+	Url(String),
+	/// A GitHub `owner/repo` at a given commit.
+	GithubDapp {
+		account: String,
+		repo: String,
+		commit: [u8; 20],
+	},
+	/// A raw content-addressed (e.g. IPFS) entry.
+	Content { url: String },
+}
+
+/// Name of the URLHint contract as registered with the global registrar.
+const URLHINT_REGISTRY_NAME: &'static str = "urlhint";
+
+/// Decodes the return value of `entries(bytes32)`: `(uint8 kind, address
+/// owner, bytes20 commit, string accountOrUrl, string repo)`.
+fn decode_entry(owner: Address, data: &Bytes) -> Result<(Entry, Address), String> {
+	let kind = decode_uint8(data, 0).ok_or_else(|| "malformed urlhint response: missing kind".to_owned())?;
+	let registered_owner = decode_address(data, 1).unwrap_or(owner);
+	let commit = decode_bytes20(data, 2).unwrap_or([0u8; 20]);
+	let account_or_url = decode_dynamic_string(data, 3).unwrap_or_default();
+	let repo = decode_dynamic_string(data, 4).unwrap_or_default();
+
+	let entry = match kind {
+		0 => Entry::Url(account_or_url),
+		1 => Entry::GithubDapp { account: account_or_url, repo: repo, commit: commit },
+		2 => Entry::Content { url: account_or_url },
+		other => return Err(format!("unknown urlhint entry kind: {}", other)),
+	};
+
+	Ok((entry, registered_owner))
+}
+
+/// Resolves content hashes via the on-chain URLHint registry, fetching and
+/// validating the referenced content against the requested hash.
+pub struct UrlHintResolver {
+	contract_client: Arc<ContractClient>,
+	fetch: FetchClient,
+}
+
+impl UrlHintResolver {
+	/// Creates a new resolver on top of the given `ContractClient` (either
+	/// `FullRegistrar` or `LightRegistrar`).
+	pub fn new(contract_client: Arc<ContractClient>, fetch: FetchClient) -> Self {
+		UrlHintResolver {
+			contract_client: contract_client,
+			fetch: fetch,
+		}
+	}
+
+	/// Looks up the URLHint contract address through the registrar's
+	/// `get(bytes32 name, string key)`, using the same dynamic "A" (address)
+	/// key convention as `RegistryClient::resolve`.
+	fn urlhint_address(&self) -> BoxFuture<Address, String> {
+		let registrar = match self.contract_client.registrar() {
+			Ok(address) => address,
+			Err(e) => return ::futures::future::err(e).boxed(),
+		};
+		let data = encode_call_word_and_bytes("get(bytes32,string)", &name_hash(URLHINT_REGISTRY_NAME), b"A");
+
+		self.contract_client.call(registrar, data)
+			.and_then(|result| {
+				decode_address(&result, 0).ok_or_else(|| "registrar returned malformed address".to_owned())
+			})
+			.boxed()
+	}
+
+	/// Resolves `content_hash` to a fetched, hash-validated `Content`.
+	pub fn resolve(&self, content_hash: H256) -> BoxFuture<Content, String> {
+		let contract_client = self.contract_client.clone();
+		let fetch = self.fetch.clone();
+		let requested_hash = content_hash;
+
+		self.urlhint_address()
+			.and_then(move |urlhint| {
+				let data = encode_call_word("entries(bytes32)", &requested_hash.0);
+				contract_client.call(urlhint, data)
+			})
+			.and_then(move |result| decode_entry(Address::default(), &result))
+			.and_then(move |(entry, owner)| {
+				let url = match entry {
+					Entry::Url(url) => url,
+					Entry::Content { url } => url,
+					Entry::GithubDapp { account, repo, commit } => {
+						format!("https://codeload.github.com/{}/{}/zip/{}", account, repo, commit.to_hex())
+					}
+				};
+
+				fetch.fetch_to_memory(&url)
+					.map_err(|e| format!("failed to fetch dapp content: {:?}", e))
+					.and_then(move |body| {
+						validate_content(&body, requested_hash)
+							.map(|mime| Content { url: url, mime: mime, owner: owner })
+					})
+					.boxed()
+			})
+			.boxed()
+	}
+}
+
+/// Validates fetched content against the requested hash: the keccak256 of
+/// the body must match the requested content hash exactly, regardless of
+/// entry kind. This is what stops a dapp served from an untrusted mirror
+/// (including a GitHub codeload mirror) from being swapped for different
+/// bytes — the registered commit only ever selects *which* archive to
+/// fetch, it never substitutes for hashing what actually came back.
+fn validate_content(body: &[u8], requested_hash: H256) -> Result<String, String> {
+	if body.sha3() != requested_hash {
+		return Err("fetched content hash mismatch".into());
+	}
+
+	Ok(guess_mime_type(body))
+}
+
+fn guess_mime_type(body: &[u8]) -> String {
+	if body.starts_with(b"PK\x03\x04") {
+		"application/zip".into()
+	} else {
+		"application/octet-stream".into()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn word_uint(value: u64) -> [u8; 32] {
+		let mut word = [0u8; 32];
+		for i in 0..8 {
+			word[31 - i] = (value >> (8 * i)) as u8;
+		}
+		word
+	}
+
+	fn encode_entries_response(kind: u8, account_or_url: &str, repo: &str) -> Bytes {
+		// layout: kind, owner, commit, offset(account_or_url), offset(repo), [len, bytes]*
+		let mut data = Vec::new();
+		data.extend_from_slice(&word_uint(kind as u64));
+		data.extend_from_slice(&[0u8; 32]); // owner
+		data.extend_from_slice(&[0u8; 32]); // commit
+		let first_offset = 5 * 32;
+		data.extend_from_slice(&word_uint(first_offset as u64));
+
+		let mut first_blob = Vec::new();
+		first_blob.extend_from_slice(&word_uint(account_or_url.len() as u64));
+		first_blob.extend_from_slice(account_or_url.as_bytes());
+		while first_blob.len() % 32 != 0 { first_blob.push(0); }
+
+		let second_offset = first_offset + first_blob.len();
+		data.extend_from_slice(&word_uint(second_offset as u64));
+		data.extend_from_slice(&first_blob);
+
+		let mut second_blob = Vec::new();
+		second_blob.extend_from_slice(&word_uint(repo.len() as u64));
+		second_blob.extend_from_slice(repo.as_bytes());
+		while second_blob.len() % 32 != 0 { second_blob.push(0); }
+		data.extend_from_slice(&second_blob);
+
+		data
+	}
+
+	#[test]
+	fn decodes_direct_url_entry() {
+		let data = encode_entries_response(0, "https://example.com/dapp.zip", "");
+		let (entry, _) = decode_entry(Address::default(), &data).unwrap();
+		assert_eq!(entry, Entry::Url("https://example.com/dapp.zip".into()));
+	}
+
+	#[test]
+	fn decodes_github_entry() {
+		let data = encode_entries_response(1, "ethereum", "parity");
+		let (entry, _) = decode_entry(Address::default(), &data).unwrap();
+		match entry {
+			Entry::GithubDapp { account, repo, .. } => {
+				assert_eq!(account, "ethereum");
+				assert_eq!(repo, "parity");
+			}
+			other => panic!("unexpected entry: {:?}", other),
+		}
+	}
+
+	#[test]
+	fn rejects_mismatched_content() {
+		let body = b"not the right bytes";
+		let wrong_hash = H256::from_slice(&"wrong".sha3());
+		assert!(validate_content(body, wrong_hash).is_err());
+	}
+}